@@ -14,6 +14,10 @@ use term_size;
 use std::path::Path;
 use std::os::unix::fs::{PermissionsExt, FileTypeExt};
 use clap::{Parser, ArgAction};
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::env;
+use unicode_width::UnicodeWidthStr;
 
 
 const KIBIBYTE: u128 = 1024;
@@ -53,6 +57,70 @@ struct Cli {
     #[clap(long, short, action=ArgAction::SetTrue)]
     all: bool,
 
+    /// Recursively list subdirectories
+    #[clap(long, short = 'R', action=ArgAction::SetTrue)]
+    recursive: bool,
+
+    /// Print a recursive tree view of directories
+    #[clap(long, action=ArgAction::SetTrue)]
+    tree: bool,
+
+    /// Maximum depth to descend when using --recursive or --tree
+    #[clap(long, value_parser)]
+    tree_depth: Option<usize>,
+
+    /// Sort by file size, largest first
+    #[clap(short = 'S', action=ArgAction::SetTrue)]
+    sort_size: bool,
+
+    /// Sort by status change time (ctime), newest first
+    #[clap(short = 't', action=ArgAction::SetTrue)]
+    sort_time: bool,
+
+    /// Do not sort, list entries in directory order
+    #[clap(short = 'U', action=ArgAction::SetTrue)]
+    unsorted: bool,
+
+    /// Reverse the active sort order
+    #[clap(short, long, action=ArgAction::SetTrue)]
+    reverse: bool,
+
+    /// Append a classification suffix (/, *, @, |, =) indicating entry type
+    #[clap(short = 'F', long, action=ArgAction::SetTrue)]
+    classify: bool,
+
+    /// Append a trailing / to directories only
+    #[clap(short = 'p', action=ArgAction::SetTrue)]
+    classify_dirs: bool,
+
+    /// Fill columns top-to-bottom then left-to-right instead of left-to-right then top-to-bottom
+    #[clap(short = 'x', long, action=ArgAction::SetTrue)]
+    across: bool,
+
+    /// Hide entries matching PATTERN, even with --all (repeatable glob)
+    #[clap(long, action=ArgAction::Append)]
+    ignore: Vec<String>,
+
+    /// Hide entries matching PATTERN, unless --all is given (repeatable glob)
+    #[clap(long, action=ArgAction::Append)]
+    hide: Vec<String>,
+
+    /// Report each directory's recursive disk usage instead of its own inode size
+    #[clap(long, action=ArgAction::SetTrue)]
+    total_size: bool,
+
+    /// With --total-size, sum actual allocated blocks rather than apparent size
+    #[clap(short = 'u', long, action=ArgAction::SetTrue)]
+    usage: bool,
+
+    /// With -l, append //DIRED//, //SUBDIRED// and //DIRED-OPTIONS// byte-offset
+    /// lines for Emacs-style programmatic navigation of the output
+    #[clap(long, action=ArgAction::SetTrue)]
+    dired: bool,
+
+    /// Print entries as a JSON array instead of human-readable text
+    #[clap(long, action=ArgAction::SetTrue)]
+    json: bool,
 
     /// Print help message
     #[clap(long, action=ArgAction::HelpLong)]
@@ -73,6 +141,109 @@ enum DeviceType{
 }
 
 
+/// The order in which collected entries are printed
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SortOrder {
+    /// Alphabetical by file_name (the default)
+    Name,
+    /// Largest size first
+    Size,
+    /// Newest status change time (ctime) first
+    Time,
+    /// No sorting, keep read_dir order
+    Unsorted,
+}
+
+
+/// Controls whether/how a trailing classification character is appended to entries
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ClassifyMode {
+    /// No classification suffix (the default)
+    None,
+    /// Suffix every entry per its file_type/executable bit (-F/--classify)
+    All,
+    /// Only append / to directories (-p)
+    DirsOnly,
+}
+
+
+/// Parsed LS_COLORS database: filetype codes (di, ln, so, pi, bd, cd, ex, fi, or) and
+/// extension globs (*.tar) mapped to their raw ANSI SGR parameter list
+#[derive(Debug, Clone, Default)]
+struct LsColors {
+    types: HashMap<String, String>,
+    extensions: HashMap<String, String>,
+}
+
+
+impl LsColors {
+    /// Parse the LS_COLORS environment variable into a lookup table, returning
+    /// None when it is unset so callers can fall back to the builtin scheme
+    fn from_env() -> Option<LsColors> {
+        let raw = env::var("LS_COLORS").ok()?;
+        let mut types = HashMap::new();
+        let mut extensions = HashMap::new();
+
+        for entry in raw.split(':') {
+            if entry.is_empty() {
+                continue;
+            }
+            let mut parts = entry.splitn(2, '=');
+            let key = parts.next().unwrap_or("");
+            let value = match parts.next() {
+                Some(v) if !v.is_empty() => v,
+                _ => continue,
+            };
+
+            if let Some(ext) = key.strip_prefix("*.") {
+                extensions.insert(format!(".{}", ext.to_lowercase()), value.to_string());
+            } else if let Some(ext) = key.strip_prefix('*') {
+                extensions.insert(ext.to_lowercase(), value.to_string());
+            } else if !key.is_empty() {
+                types.insert(key.to_string(), value.to_string());
+            }
+        }
+
+        if types.is_empty() && extensions.is_empty() {
+            None
+        } else {
+            Some(LsColors{types: types, extensions: extensions})
+        }
+    }
+
+    /// Look up the raw ANSI codes for an entry, preferring the longest matching
+    /// extension suffix (case-insensitive) for regular files before falling
+    /// back to the filetype code
+    fn lookup(&self, file_type: &DeviceType, executable: bool, file_name: &str, broken_symlink: bool) -> Option<&str> {
+        if matches!(file_type, DeviceType::File) {
+            let lower = file_name.to_lowercase();
+            let mut best: Option<(usize, &str)> = None;
+            for (ext, value) in self.extensions.iter() {
+                if lower.ends_with(ext.as_str()) {
+                    if best.map_or(true, |(best_len, _)| ext.len() > best_len) {
+                        best = Some((ext.len(), value.as_str()));
+                    }
+                }
+            }
+            if let Some((_, value)) = best {
+                return Some(value);
+            }
+        }
+
+        let type_key = match file_type {
+            DeviceType::Dir => "di",
+            DeviceType::Symlink => if broken_symlink { "or" } else { "ln" },
+            DeviceType::Socket => "so",
+            DeviceType::Fifo => "pi",
+            DeviceType::BlockDevice => "bd",
+            DeviceType::CharDevice => "cd",
+            DeviceType::File => if executable { "ex" } else { "fi" },
+        };
+        self.types.get(type_key).map(|v| v.as_str())
+    }
+}
+
+
 /// Each DirectoryItem is represted here using different parts sliced from the DirEntry struct
 #[derive(Debug, Clone)]
 struct DirectoryItem <'a>{
@@ -87,6 +258,7 @@ struct DirectoryItem <'a>{
     /// size: file size
     /// user: user created the file
     /// executable: if file is executable
+    /// broken_symlink: if file_type is Symlink and its target cannot be resolved
     /// defaults: Defaults value passed in.
     path_abs: String,
     path_disp: String,
@@ -99,6 +271,7 @@ struct DirectoryItem <'a>{
     size: u128,
     user: User,
     executable: bool,
+    broken_symlink: bool,
     defaults: &'a Defaults,
 }
 
@@ -110,10 +283,27 @@ struct Defaults {
     /// human_readable: Marking if size values should be displayed in a human readable format
     /// long_form: list directories in long form
     /// all: Bool denoting if full path value should be displayed
+    /// recursive: descend into subdirectories
+    /// tree: render subdirectories as an indented tree
+    /// tree_depth: maximum depth to descend for recursive/tree
     colourize: bool,
     human_readable: bool,
     long_form: bool,
     all: bool,
+    recursive: bool,
+    tree: bool,
+    tree_depth: Option<usize>,
+    sort_order: SortOrder,
+    reverse: bool,
+    ls_colors: Option<LsColors>,
+    classify: ClassifyMode,
+    across: bool,
+    ignore_patterns: Vec<glob::Pattern>,
+    hide_patterns: Vec<glob::Pattern>,
+    total_size: bool,
+    usage: bool,
+    dired: bool,
+    json: bool,
 }
 
 
@@ -133,6 +323,7 @@ impl DirectoryItem<'_>  {
         let nlink = metadata.nlink();
         let time = metadata.ctime();
         let size = metadata.size();
+        let broken_symlink = matches!(file_type, DeviceType::Symlink) && fs::metadata(path).is_err();
         DirectoryItem{
             file_type: file_type,
             file_name: path_buf.file_name().unwrap().to_str().unwrap().to_string(),
@@ -143,7 +334,10 @@ impl DirectoryItem<'_>  {
             size: size as u128,
             user:user,
             executable: executable,
-            path_abs: fs::canonicalize(path).unwrap().display().to_string(),
+            broken_symlink: broken_symlink,
+            path_abs: fs::canonicalize(path).map(|p| p.display().to_string())
+                .unwrap_or_else(|_| fs::read_link(path).map(|p| p.display().to_string())
+                    .unwrap_or_else(|_| path_buf.display().to_string())),
             path_disp: path_buf.display().to_string(),
             defaults: defaults,
         }
@@ -189,6 +383,7 @@ impl DirectoryItem<'_>  {
         let nlink = metadata.nlink();
         let time = metadata.ctime();
         let size = metadata.size();
+        let broken_symlink = matches!(file_type, DeviceType::Symlink) && fs::metadata(&path_buf).is_err();
         DirectoryItem{
             file_type: file_type,
             file_name: path_buf.file_name().unwrap().to_str().unwrap().to_string(),
@@ -199,7 +394,10 @@ impl DirectoryItem<'_>  {
             size: size as u128,
             user:user,
             executable: executable,
-            path_abs: fs::canonicalize(path_buf.clone()).unwrap().display().to_string(),
+            broken_symlink: broken_symlink,
+            path_abs: fs::canonicalize(&path_buf).map(|p| p.display().to_string())
+                .unwrap_or_else(|_| fs::read_link(&path_buf).map(|p| p.display().to_string())
+                    .unwrap_or_else(|_| path_buf.display().to_string())),
             path_disp: path_buf.clone().display().to_string(),
             defaults: defaults,
         }
@@ -230,8 +428,20 @@ impl DirectoryItem<'_>  {
         return out_fn
     }
 
-    fn file_name_length(&self) -> usize {
-        self.file_name.len()
+    /// The trailing classification character for -F/--classify or -p, if any
+    fn classify_suffix(&self) -> &'static str {
+        match self.defaults.classify {
+            ClassifyMode::None => "",
+            ClassifyMode::DirsOnly => if matches!(self.file_type, DeviceType::Dir) { "/" } else { "" },
+            ClassifyMode::All => match self.file_type {
+                DeviceType::Dir => "/",
+                DeviceType::Symlink => "@",
+                DeviceType::Fifo => "|",
+                DeviceType::Socket => "=",
+                DeviceType::File => if self.executable { "*" } else { "" },
+                _ => "",
+            },
+        }
     }
 
     fn file_path(&self) -> String {
@@ -239,24 +449,35 @@ impl DirectoryItem<'_>  {
         if self.defaults.long_form {
             display = &self.path_abs;
         }
+
+        if self.defaults.colourize && colored::control::SHOULD_COLORIZE.should_colorize() {
+            if let Some(ls_colors) = &self.defaults.ls_colors {
+                if let Some(codes) = ls_colors.lookup(&self.file_type, self.executable, &self.file_name, self.broken_symlink) {
+                    let coloured = format!("\x1b[{}m{}\x1b[0m", codes, display);
+                    return self.display_path(&coloured);
+                }
+            }
+        }
+
         let func_colour = self.pick_colour();
         return self.display_path(&func_colour(display));
 
     }
 
-    fn display_path(&self, display: &ColoredString) -> String {
+    fn display_path<T: std::fmt::Display>(&self, display: &T) -> String {
+        let suffix = self.classify_suffix();
         let out_str = match self.file_type {
             DeviceType::Symlink => if self.defaults.long_form {
-                format!("{} -> {}", display, self.path_abs)
-            } else {  
-                format!("{}", display)
+                format!("{}{} -> {}", display, suffix, self.path_abs)
+            } else {
+                format!("{}{}", display, suffix)
             },
-            DeviceType::BlockDevice => format!("{}", display),
-            DeviceType::CharDevice => format!("{}", display),
-            DeviceType::Fifo => format!("{}", display),
-            DeviceType::Socket => format!("{}", display),
-            DeviceType::Dir => format!("{}", display),
-            _ => format!("{}", display)
+            DeviceType::BlockDevice => format!("{}{}", display, suffix),
+            DeviceType::CharDevice => format!("{}{}", display, suffix),
+            DeviceType::Fifo => format!("{}{}", display, suffix),
+            DeviceType::Socket => format!("{}{}", display, suffix),
+            DeviceType::Dir => format!("{}{}", display, suffix),
+            _ => format!("{}{}", display, suffix)
             };
             return out_str;
     }
@@ -289,6 +510,37 @@ impl DirectoryItem<'_>  {
         }
     }
 
+    fn type_name(&self) -> &'static str {
+        match self.file_type {
+            DeviceType::Dir => "directory",
+            DeviceType::BlockDevice => "block_device",
+            DeviceType::CharDevice => "char_device",
+            DeviceType::Symlink => "symlink",
+            DeviceType::Socket => "socket",
+            DeviceType::Fifo => "fifo",
+            DeviceType::File => "file",
+        }
+    }
+
+    /// Render this entry as a single JSON object for --json output
+    fn to_json(&self) -> String {
+        let mtime = fs::symlink_metadata(&self.path_disp)
+            .map(|m| m.mtime().to_string())
+            .unwrap_or_else(|_| "null".to_string());
+        format!(
+            "{{\"name\":{},\"path\":{},\"type\":\"{}\",\"mode\":\"{}\",\"nlink\":{},\"user\":{},\"group\":{},\"size\":{},\"mtime\":{}}}",
+            json_escape(&self.file_name),
+            json_escape(&self.path_abs),
+            self.type_name(),
+            self.permissions_string(),
+            self.nlink,
+            json_escape(&self.user.name),
+            json_escape(&self.group.name),
+            self.size,
+            mtime,
+        )
+    }
+
     fn convert_units(size: u128) -> String {
         let magnitude = match size as u128 {
             e if e < KIBIBYTE => format!("{}B", e),
@@ -339,11 +591,23 @@ impl DirectoryItem<'_>  {
     }
 
     fn print_long(&self, file_size_pad: usize, group_pad: usize, user_pad: usize, inodes: usize) {
-        println!("{} {:<inode_p$} {:<gpad$} {:<upad$} {:<szpad$} {} {}",
+        let (line, _, _) = self.long_line(file_size_pad, group_pad, user_pad, inodes);
+        println!("{}", line);
+    }
+
+    /// Build the long-form line for this entry, returning it along with the
+    /// (start, end) byte range of the name field within the line: the possibly
+    /// coloured name plus any classify suffix and, for symlinks, the ` -> target`
+    fn long_line(&self, file_size_pad: usize, group_pad: usize, user_pad: usize, inodes: usize) -> (String, usize, usize) {
+        let prefix = format!("{} {:<inode_p$} {:<gpad$} {:<upad$} {:<szpad$} {} ",
                  self.permissions_string(),
                  self.nlink, self.group.name, self.user.name,
-                 self.size(), self.time(), self.file_path(), inode_p=inodes,
+                 self.size(), self.time(), inode_p=inodes,
                  gpad=group_pad, upad=user_pad, szpad=file_size_pad);
+        let name = self.file_path();
+        let start = prefix.len();
+        let end = start + name.len();
+        (format!("{}{}", prefix, name), start, end)
     }
 }
 
@@ -359,7 +623,7 @@ where T:  Ord {
 
 
 //fn list_contents<'a>(dir: &'a Path, defaults: &'a Defaults) -> Vec<Box<DirectoryItem<'a>>> {
-fn list_contents<'a>(dir: &'a Path, defaults: &'a Defaults) -> Vec<Box<DirectoryItem<'a>>> {
+fn list_contents<'a>(dir: &Path, defaults: &'a Defaults) -> Vec<Box<DirectoryItem<'a>>> {
 
     let mut outputs: Vec<Box<DirectoryItem>> = Vec::new();
     if dir.is_dir() {
@@ -368,7 +632,7 @@ fn list_contents<'a>(dir: &'a Path, defaults: &'a Defaults) -> Vec<Box<Directory
         for path in paths {
 
             let data = path.unwrap();
-            if !defaults.all && data.file_name().to_str().unwrap().starts_with(".") {
+            if is_hidden_entry(data.file_name().to_str().unwrap(), defaults) {
                 continue;
             }
             let new_value = Box::new(DirectoryItem::from_dir_entry(data, defaults));
@@ -378,118 +642,524 @@ fn list_contents<'a>(dir: &'a Path, defaults: &'a Defaults) -> Vec<Box<Directory
         let file = Box::new(DirectoryItem::from_file(dir, defaults));
         outputs.push(file);
     }
+    if defaults.total_size {
+        for item in outputs.iter_mut() {
+            if let DeviceType::Dir = item.file_type {
+                let mut seen = HashSet::new();
+                item.size = dir_usage(Path::new(&item.path_abs), defaults.usage, &mut seen);
+            }
+        }
+    }
     return outputs;
 }
 
 
-/// Calculate the number of entries to show per a line
-fn calculate_column_width(col_width: usize, longest_char: usize) -> usize {
-    if col_width == 0 {
-        return 1
+/// Recursively sum a directory's disk usage, deduplicating hardlinks via
+/// (dev, inode) and never following symlinks. With `use_blocks`, sums actual
+/// allocated blocks (512-byte units) rather than apparent file size.
+fn dir_usage(dir: &Path, use_blocks: bool, seen: &mut HashSet<(u64, u64)>) -> u128 {
+    let mut total: u128 = 0;
+    let paths = match fs::read_dir(dir) {
+        Ok(p) => p,
+        Err(_) => return total,
+    };
+    for path in paths {
+        let entry = match path {
+            Ok(e) => e,
+            Err(_) => continue,
+        };
+        let metadata = match entry.metadata() {
+            Ok(m) => m,
+            Err(_) => continue,
+        };
+        if !seen.insert((metadata.dev(), metadata.ino())) {
+            continue;
+        }
+        if metadata.is_dir() {
+            total += dir_usage(&entry.path(), use_blocks, seen);
+        } else if !metadata.file_type().is_symlink() {
+            total += if use_blocks {
+                metadata.blocks() as u128 * 512
+            } else {
+                metadata.size() as u128
+            };
+        }
     }
+    total
+}
 
-    if longest_char > (col_width / 2) {
-        return 1
+
+/// True if an entry should be skipped per the hidden-dot filter (-a) and the
+/// --ignore/--hide glob patterns. --ignore always hides a match; --hide only
+/// hides it when --all has not been passed, mirroring GNU ls semantics.
+fn is_hidden_entry(name: &str, defaults: &Defaults) -> bool {
+    if !defaults.all && name.starts_with(".") {
+        return true;
+    }
+    if defaults.ignore_patterns.iter().any(|p| p.matches(name)) {
+        return true;
+    }
+    if !defaults.all && defaults.hide_patterns.iter().any(|p| p.matches(name)) {
+        return true;
     }
+    false
+}
+
 
-    let values_per_column = col_width / longest_char;
-    return values_per_column;
+/// Compile --ignore/--hide glob patterns, warning on (and skipping) invalid ones
+fn compile_patterns(patterns: &[String]) -> Vec<glob::Pattern> {
+    let mut compiled = Vec::new();
+    for pattern in patterns {
+        match glob::Pattern::new(pattern) {
+            Ok(p) => compiled.push(p),
+            Err(e) => eprintln!("Invalid pattern '{}': {}", pattern, e),
+        }
+    }
+    compiled
 }
 
-/// Strings were not being padded nicely with the added control charactars
-fn pad_value(input: &DirectoryItem, length: usize){
-    print!("{}", input.file_path());
-    let spaces = " ".repeat(length - input.file_name.len());
-    print!("{}", spaces);
+
+/// A directory entry together with its children, used to build --tree and -R output
+struct DirectoryNode<'a> {
+    item: Box<DirectoryItem<'a>>,
+    children: Vec<DirectoryNode<'a>>,
 }
 
 
-fn main(){
+/// Depth-first walk that builds a DirectoryNode tree rooted at `item`, descending into
+/// directories up to `defaults.tree_depth` (unbounded when not set)
+fn build_tree<'a>(item: Box<DirectoryItem<'a>>, defaults: &'a Defaults, depth: usize) -> DirectoryNode<'a> {
+    let mut children = Vec::new();
+    let within_depth = match defaults.tree_depth {
+        Some(max_depth) => depth < max_depth,
+        None => true,
+    };
+    if matches!(item.file_type, DeviceType::Dir) && within_depth {
+        if let Ok(paths) = fs::read_dir(Path::new(&item.path_abs)) {
+            for entry in paths {
+                let data = entry.unwrap();
+                let name = data.file_name();
+                let name = name.to_str().unwrap().to_string();
+                if name == "." || name == ".." {
+                    continue;
+                }
+                if is_hidden_entry(&name, defaults) {
+                    continue;
+                }
+                let child_item = Box::new(DirectoryItem::from_dir_entry(data, defaults));
+                children.push(build_tree(child_item, defaults, depth + 1));
+            }
+        }
+    }
+    DirectoryNode{item: item, children: children}
+}
 
-    let args = Cli::parse();
-    let defaults = Defaults{
-        colourize: args.colourize, 
-        human_readable: args.human,
-        long_form: args.long,
-        all: args.all};
 
-    let mut outputs: Vec<Box<DirectoryItem>> = Vec::new();
-    for path in args.files.iter(){
-        let fp_path = Path::new(path);
-        if !fp_path.exists(){
-            eprintln!("Path does not exist: {}", fp_path.display());
+/// Render a DirectoryNode tree using the Unicode branch connectors tree(1) uses
+fn print_tree(node: &DirectoryNode, prefix: &str, is_last: bool, is_root: bool) {
+    if is_root {
+        println!("{}", node.item.file_path());
+    } else {
+        let connector = if is_last { "└── " } else { "├── " };
+        println!("{}{}{}", prefix, connector, node.item.file_path());
+    }
+
+    let child_prefix = if is_root {
+        String::new()
+    } else if is_last {
+        format!("{}    ", prefix)
+    } else {
+        format!("{}│   ", prefix)
+    };
+
+    let count = node.children.len();
+    for (idx, child) in node.children.iter().enumerate() {
+        print_tree(child, &child_prefix, idx + 1 == count, false);
+    }
+}
+
+
+/// Recursively list `dir` and every subdirectory beneath it, returning one
+/// (header path, entries) group per directory visited, depth-first
+fn list_recursive<'a>(dir: &Path, defaults: &'a Defaults, depth: usize) -> Vec<(String, Vec<Box<DirectoryItem<'a>>>)> {
+    let mut groups = Vec::new();
+    let entries = list_contents(dir, defaults);
+
+    let within_depth = match defaults.tree_depth {
+        Some(max_depth) => depth < max_depth,
+        None => true,
+    };
+    let mut sub_dirs: Vec<String> = Vec::new();
+    if within_depth {
+        for entry in entries.iter() {
+            if matches!(entry.file_type, DeviceType::Dir) {
+                sub_dirs.push(entry.path_disp.clone());
+            }
+        }
+    }
+
+    groups.insert(0, (dir.display().to_string(), entries));
+    for sub_dir in sub_dirs.iter() {
+        let mut sub_groups = list_recursive(Path::new(sub_dir), defaults, depth + 1);
+        groups.append(&mut sub_groups);
+    }
+    groups
+}
+
+
+/// Inter-column padding between grid cells
+const GRID_PADDING: usize = 2;
+
+
+/// Remove ANSI SGR escape sequences (`\x1b[...m`) so width calculations only
+/// count visible glyphs
+fn strip_ansi_codes(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            for next in chars.by_ref() {
+                if next.is_ascii_alphabetic() {
+                    break;
+                }
+            }
             continue;
         }
-        let mut out = list_contents(fp_path, &defaults);
-        outputs.append(&mut out);
+        result.push(c);
     }
+    result
+}
 
 
-    // Get relevant values needed to sort or pad outputs
-    // TODO These values should be set when iterating the directorys in the future
-    let mut longest_value: usize = 0;
-    let mut files_per_row: usize = 0;
-    let mut largest_file: usize = 0;
-    let mut largest_group: usize = 0;
-    let mut largest_user: usize = 0;
-    let mut inodes: u64 = 0;
-    let mut inodes_u: usize = 0;
-    if !defaults.long_form{
-        longest_value =  match outputs.iter().map(|x| (*x).file_name_length()).max(){
-            Some(x) => x + 1, // Add padding to variable for longest entry
-            None => return (),
-        };
+/// Escape a string for embedding as a JSON string literal (double quotes included)
+fn json_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+
+/// The printed width of `s`, ignoring ANSI colour escapes and counting wide
+/// (e.g. CJK) glyphs as two columns
+fn display_width(s: &str) -> usize {
+    UnicodeWidthStr::width(strip_ansi_codes(s).as_str())
+}
+
+
+/// Find the widest grid (trying column counts from the largest plausible value
+/// downward) whose per-column max widths, plus inter-column padding, fit within
+/// `term_width`. Returns (columns, rows, per-column widths).
+fn fit_grid(widths: &[usize], term_width: usize, across: bool) -> (usize, usize, Vec<usize>) {
+    let n = widths.len();
+    let narrowest = *widths.iter().min().unwrap_or(&1);
+    let max_columns = n.min(term_width / max(narrowest, 1)).max(1);
+
+    for columns in (1..=max_columns).rev() {
+        let rows = n.div_ceil(columns);
+        let mut column_widths = vec![0usize; columns];
+        for (k, w) in widths.iter().enumerate() {
+            let col = if across { k / rows } else { k % columns };
+            column_widths[col] = max(column_widths[col], *w);
+        }
+        let total = column_widths.iter().sum::<usize>() + GRID_PADDING * columns.saturating_sub(1);
+        if total <= term_width || columns == 1 {
+            return (columns, rows, column_widths);
+        }
+    }
+    (1, n, vec![*widths.iter().max().unwrap_or(&0)])
+}
+
 
+/// Print entries packed into as many columns as fit the terminal width, using
+/// each entry's measured display width rather than assuming uniform columns
+fn print_grid(outputs: &[Box<DirectoryItem>], defaults: &Defaults, term_width: usize) {
+    let paths: Vec<String> = outputs.iter().map(|x| x.file_path()).collect();
+    let widths: Vec<usize> = paths.iter().map(|p| display_width(p)).collect();
+    let (columns, rows, column_widths) = fit_grid(&widths, term_width, defaults.across);
+
+    for row in 0..rows {
+        let mut line = String::new();
+        #[allow(clippy::needless_range_loop)]
+        for col in 0..columns {
+            let k = if defaults.across { col * rows + row } else { row * columns + col };
+            if k >= paths.len() {
+                break;
+            }
+            line.push_str(&paths[k]);
+
+            let next_k = if defaults.across { (col + 1) * rows + row } else { row * columns + col + 1 };
+            if col + 1 < columns && next_k < paths.len() {
+                line.push_str(&" ".repeat(column_widths[col] + GRID_PADDING - widths[k]));
+            }
+        }
+        println!("{}", line);
+    }
+}
+
+
+/// Sort collected entries per `defaults.sort_order`, then reverse if `defaults.reverse`
+/// is set. Uses a stable sort so ties keep directory (read_dir) order.
+fn sort_entries(outputs: &mut Vec<Box<DirectoryItem>>, defaults: &Defaults) {
+    match defaults.sort_order {
+        SortOrder::Name => outputs.sort_by_key(|a| a.file_name.clone()),
+        SortOrder::Size => outputs.sort_by_key(|a| std::cmp::Reverse(a.size)),
+        SortOrder::Time => outputs.sort_by_key(|a| std::cmp::Reverse(a.time)),
+        SortOrder::Unsorted => {}
+    }
+    if defaults.reverse {
+        outputs.reverse();
+    }
+}
+
+
+/// Accumulates the full emitted long-form output along with the byte offsets
+/// of every filename and directory header, for --dired
+struct DiredTracker {
+    buffer: String,
+    file_offsets: Vec<(usize, usize)>,
+    subdir_offsets: Vec<(usize, usize)>,
+}
+
+impl DiredTracker {
+    fn new() -> Self {
+        DiredTracker {
+            buffer: String::new(),
+            file_offsets: Vec::new(),
+            subdir_offsets: Vec::new(),
+        }
+    }
+
+    fn push_line(&mut self, line: &str) {
+        self.buffer.push_str(line);
+        self.buffer.push('\n');
+    }
+
+    fn push_header(&mut self, header: &str) {
+        let start = self.buffer.len();
+        self.push_line(header);
+        self.subdir_offsets.push((start, start + header.len()));
+    }
+
+    fn push_entry(&mut self, line: &str, name_start: usize, name_end: usize) {
+        let base = self.buffer.len();
+        self.file_offsets.push((base + name_start, base + name_end));
+        self.push_line(line);
+    }
+
+    fn finish(self) {
+        print!("{}", self.buffer);
+        let files: Vec<String> = self.file_offsets.iter().map(|(s, e)| format!("{} {}", s, e)).collect();
+        let subdirs: Vec<String> = self.subdir_offsets.iter().map(|(s, e)| format!("{} {}", s, e)).collect();
+        println!("//DIRED// {}", files.join(" "));
+        println!("//SUBDIRED// {}", subdirs.join(" "));
+        println!("//DIRED-OPTIONS// --quoting-style=literal");
+    }
+}
+
+
+/// Print a single group of already-collected entries, either in long form or
+/// packed into columns, matching the previous single-pass behaviour of main().
+/// When `tracker` is `Some`, long-form lines are appended to it instead of
+/// being printed directly, so --dired can report byte offsets at the end.
+fn print_listing(outputs: &mut Vec<Box<DirectoryItem>>, defaults: &Defaults, tracker: &mut Option<DiredTracker>) {
+    if outputs.is_empty() {
+        return;
+    }
+    sort_entries(outputs, defaults);
+
+    if defaults.json {
+        let items: Vec<String> = outputs.iter().map(|item| item.to_json()).collect();
+        println!("[{}]", items.join(","));
+        return;
+    }
+
+    if !defaults.long_form {
         // Get Term size for creating the output file
         #[allow(unused_assignments)]
         let (width, _) = match term_size::dimensions() {
             Some(x) => x,
             None => panic!(),
         };
+        print_grid(outputs, defaults, width);
+        return;
+    }
+
+    // Get relevant values needed to pad the long-form columns
+    let mut largest_file: usize = 0;
+    let mut largest_group: usize = 0;
+    let mut largest_user: usize = 0;
+    let mut inodes: u64 = 0;
+    for val in outputs.iter() {
+        let largest_file_t = (*val).size().len();
+        let largest_group_t = (*val).group.name.len();
+        let largest_user_t = (*val).user.name.len();
+        let inodes_t = (*val).nlink;
+        largest_file = max(largest_file_t, largest_file);
+        largest_group = max(largest_group_t, largest_group);
+        largest_user = max(largest_user_t, largest_user);
+        inodes = max(inodes_t, inodes);
+    }
+    // Get number of digits in the printed inodes representation
+    let inodes_u = (inodes.checked_ilog10().unwrap_or(0) + 1) as usize;
 
-        // Calculate how many values to print
-        files_per_row = calculate_column_width(width, longest_value);
-    }else{
-        for val in outputs.iter() {
-            let largest_file_t = (*val).size().len();
-            let largest_group_t = (*val).group.name.len();
-            let largest_user_t = (*val).user.name.len();
-            let inodes_t = (*val).nlink;
-            largest_file = max(largest_file_t, largest_file);
-            largest_group = max(largest_group_t, largest_group);
-            largest_user = max(largest_user_t, largest_user);
-            inodes = max(inodes_t, inodes);
-        }
-        // Get number of digits in the printed inodes representation
-        inodes_u = (inodes.checked_ilog10().unwrap_or(0) + 1) as usize;
-    }
-
-    // Print outputs
-    let mut idx = 1;
     for di in outputs.iter() {
-        if defaults.long_form {
+        if let Some(t) = tracker.as_mut() {
+            let (line, start, _) = (*di).long_line(largest_file, largest_group, largest_user, inodes_u);
+            // Strip any colour escapes from the name so the reported offsets
+            // are byte-accurate into the actual (uncoloured) dired buffer; the
+            // range covers the displayed path only, not a classify suffix or
+            // a symlink's ` -> target`
+            let plain_name = strip_ansi_codes(&line[start..]);
+            let plain_line = format!("{}{}", &line[..start], plain_name);
+            let end = start + (*di).path_abs.len();
+            t.push_entry(&plain_line, start, end);
+        } else {
             (*di).print_long(largest_file, largest_group, largest_user, inodes_u);
-        }else{
-            pad_value(&(*di), longest_value);
-            if idx % files_per_row == 0 {
-                println!();
+        }
+    }
+}
+
+
+fn main(){
+
+    let args = Cli::parse();
+    let classify = if args.classify {
+        ClassifyMode::All
+    } else if args.classify_dirs {
+        ClassifyMode::DirsOnly
+    } else {
+        ClassifyMode::None
+    };
+    let sort_order = if args.unsorted {
+        SortOrder::Unsorted
+    } else if args.sort_size {
+        SortOrder::Size
+    } else if args.sort_time {
+        SortOrder::Time
+    } else {
+        SortOrder::Name
+    };
+    let defaults = Defaults{
+        colourize: args.colourize,
+        human_readable: args.human,
+        long_form: args.long,
+        all: args.all,
+        recursive: args.recursive,
+        tree: args.tree,
+        tree_depth: args.tree_depth,
+        sort_order: sort_order,
+        reverse: args.reverse,
+        ls_colors: LsColors::from_env(),
+        classify: classify,
+        across: args.across,
+        ignore_patterns: compile_patterns(&args.ignore),
+        hide_patterns: compile_patterns(&args.hide),
+        total_size: args.total_size,
+        usage: args.usage,
+        dired: args.dired,
+        json: args.json};
+
+    if defaults.tree {
+        for path in args.files.iter() {
+            let fp_path = Path::new(path);
+            if !fp_path.exists(){
+                eprintln!("Path does not exist: {}", fp_path.display());
+                continue;
+            }
+            let root = Box::new(DirectoryItem::from_file(fp_path, &defaults));
+            let tree = build_tree(root, &defaults, 0);
+            print_tree(&tree, "", true, true);
+        }
+        return;
+    }
+
+    let mut tracker = if defaults.dired { Some(DiredTracker::new()) } else { None };
+
+    if defaults.recursive {
+        for path in args.files.iter() {
+            let fp_path = Path::new(path);
+            if !fp_path.exists(){
+                eprintln!("Path does not exist: {}", fp_path.display());
+                continue;
             }
+            let mut groups = list_recursive(fp_path, &defaults, 0);
+            let multiple = groups.len() > 1;
+            for (idx, (group_path, entries)) in groups.iter_mut().enumerate() {
+                if multiple {
+                    if let Some(t) = tracker.as_mut() {
+                        if idx > 0 {
+                            t.push_line("");
+                        }
+                        t.push_header(&format!("{}:", group_path));
+                    } else {
+                        if idx > 0 {
+                            println!();
+                        }
+                        println!("{}:", group_path);
+                    }
+                }
+                print_listing(entries, &defaults, &mut tracker);
+            }
+        }
+        if let Some(t) = tracker {
+            t.finish();
+        }
+        return;
+    }
+
+    let mut outputs: Vec<Box<DirectoryItem>> = Vec::new();
+    for path in args.files.iter(){
+        let fp_path = Path::new(path);
+        if !fp_path.exists(){
+            eprintln!("Path does not exist: {}", fp_path.display());
+            continue;
         }
-        idx+=1
+        let mut out = list_contents(fp_path, &defaults);
+        outputs.append(&mut out);
     }
 
-    if !defaults.long_form && (idx - 1) % files_per_row != 0 {
-        println!();
+    print_listing(&mut outputs, &defaults, &mut tracker);
+    if let Some(t) = tracker {
+        t.finish();
     }
+}
+
+
 
+#[test]
+fn grid_fit_packs_short_names_into_many_columns(){
+    let widths = vec![2, 2, 2, 2, 2, 2];
+    let (columns, rows, column_widths) = fit_grid(&widths, 22, false);
+    assert_eq!(columns, 6);
+    assert_eq!(rows, 1);
+    assert_eq!(column_widths, vec![2, 2, 2, 2, 2, 2]);
 }
 
 
+#[test]
+fn grid_fit_falls_back_to_single_column(){
+    let widths = vec![40, 40, 40];
+    let (columns, _, _) = fit_grid(&widths, 20, false);
+    assert_eq!(columns, 1);
+}
+
 
 #[test]
-fn column_widths(){
-    assert_eq!(calculate_column_width(10, 5), 2);
-    assert_eq!(calculate_column_width(10, 6), 1);
+fn display_width_ignores_ansi_escapes(){
+    assert_eq!(display_width("\x1b[34mdir\x1b[0m"), 3);
+    assert_eq!(display_width("plain"), 5);
 }
 
 
@@ -518,3 +1188,53 @@ fn permissions_triplet(){
     assert_eq!("rw-", DirectoryItem::permissions_triplet(6));
     assert_eq!("rwx", DirectoryItem::permissions_triplet(7));
 }
+
+
+#[test]
+fn ls_colors_from_env_parses_types_and_extensions(){
+    env::set_var("LS_COLORS", "di=01;34:*.tar=01;31");
+    let colors = LsColors::from_env().unwrap();
+    assert_eq!(colors.types.get("di"), Some(&"01;34".to_string()));
+    assert_eq!(colors.extensions.get(".tar"), Some(&"01;31".to_string()));
+    env::remove_var("LS_COLORS");
+}
+
+
+#[test]
+fn ls_colors_lookup_requires_extension_boundary(){
+    let mut extensions = HashMap::new();
+    extensions.insert(".tar".to_string(), "01;31".to_string());
+    let colors = LsColors{types: HashMap::new(), extensions};
+    assert_eq!(colors.lookup(&DeviceType::File, false, "archive.tar", false), Some("01;31"));
+    assert_eq!(colors.lookup(&DeviceType::File, false, "star", false), None);
+}
+
+
+#[test]
+fn ls_colors_lookup_prefers_longest_suffix(){
+    let mut extensions = HashMap::new();
+    extensions.insert(".tar".to_string(), "01;31".to_string());
+    extensions.insert(".tar.gz".to_string(), "01;35".to_string());
+    let colors = LsColors{types: HashMap::new(), extensions};
+    assert_eq!(colors.lookup(&DeviceType::File, false, "archive.tar.gz", false), Some("01;35"));
+}
+
+
+#[test]
+fn ls_colors_lookup_is_case_insensitive(){
+    let mut extensions = HashMap::new();
+    extensions.insert(".tar".to_string(), "01;31".to_string());
+    let colors = LsColors{types: HashMap::new(), extensions};
+    assert_eq!(colors.lookup(&DeviceType::File, false, "ARCHIVE.TAR", false), Some("01;31"));
+}
+
+
+#[test]
+fn ls_colors_lookup_orphan_symlink_uses_or_code(){
+    let mut types = HashMap::new();
+    types.insert("ln".to_string(), "01;36".to_string());
+    types.insert("or".to_string(), "05;37;41".to_string());
+    let colors = LsColors{types, extensions: HashMap::new()};
+    assert_eq!(colors.lookup(&DeviceType::Symlink, false, "link", false), Some("01;36"));
+    assert_eq!(colors.lookup(&DeviceType::Symlink, false, "link", true), Some("05;37;41"));
+}